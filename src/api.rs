@@ -0,0 +1,69 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use google_sheets4::hyper::service::{make_service_fn, service_fn};
+use google_sheets4::hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, info};
+use serde::Serialize;
+
+use crate::{load_ids, run_tick, Config, SharedState};
+
+/// Spawns the status/control API in the background, disabled unless the
+/// caller passes a listen address (`API_LISTEN_ADDR`). Exposes `GET
+/// /healthz`, `GET /status`, `POST /trigger` and `POST /reload-ids`.
+pub fn spawn(addr: SocketAddr, config: Arc<Config>, state: Arc<SharedState>) {
+    tokio::spawn(async move {
+        let make_svc = make_service_fn(move |_conn| {
+            let config = config.clone();
+            let state = state.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle(req, config.clone(), state.clone()))) }
+        });
+
+        info!("Status API listening on {addr}");
+        if let Err(e) = Server::bind(&addr).serve(make_svc).await {
+            error!("Status API server error: {e:?}");
+        }
+    });
+}
+
+async fn handle(req: Request<Body>, config: Arc<Config>, state: Arc<SharedState>) -> Result<Response<Body>, Infallible> {
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/healthz") => Response::new(Body::from("ok")),
+        (&Method::GET, "/status") => json_response(&*state.status.lock().await),
+        (&Method::POST, "/trigger") => match run_tick(&config, &state).await {
+            Ok(new_data) => json_response(&serde_json::json!({"row_count": new_data.len()})),
+            Err(e) => {
+                error!("Manual /trigger failed: {e:?}");
+                error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+            }
+        },
+        (&Method::POST, "/reload-ids") => {
+            let ids = load_ids("ids.txt");
+            let count = ids.len();
+            *state.ids.lock().unwrap() = ids;
+            info!("Reloaded ids.txt via /reload-ids ({count} entries)");
+            json_response(&serde_json::json!({"ids_loaded": count}))
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "not found"),
+    };
+    Ok(response)
+}
+
+fn json_response<T: Serialize>(value: &T) -> Response<Body> {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap_or_else(|_| Response::new(Body::empty())),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::json!({"error": message}).to_string()))
+        .unwrap_or_else(|_| Response::new(Body::empty()))
+}
@@ -0,0 +1,99 @@
+use google_sheets4 as sheets4;
+use google_sheets4::hyper::Client;
+use google_sheets4::hyper::client::HttpConnector;
+use google_sheets4::hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use log::info;
+use sheets4::oauth2::authenticator::{ApplicationDefaultCredentialsAuthenticator, Authenticator};
+use sheets4::oauth2::{
+    self, ApplicationDefaultCredentialsFlowOpts, InstalledFlowAuthenticator,
+    InstalledFlowReturnMethod, ServiceAccountAuthenticator,
+};
+use sheets4::Sheets;
+
+pub type SheetsClient = Sheets<HttpsConnector<HttpConnector>>;
+
+/// The underlying authenticator, kept around (cheaply clonable) so other
+/// Google REST APIs such as Cloud Storage can mint tokens with the same
+/// credentials `authenticate` picked.
+pub type SheetsAuthenticator = Authenticator<HttpsConnector<HttpConnector>>;
+
+/// Selects how `authenticate` obtains credentials, via the `AUTH_METHOD`
+/// env var, so the bot can run unattended instead of requiring an
+/// interactive OAuth redirect.
+pub(crate) enum AuthMethod {
+    Installed,
+    ServiceAccount,
+    Metadata,
+}
+
+impl AuthMethod {
+    pub(crate) fn from_env() -> Self {
+        match std::env::var("AUTH_METHOD").ok().as_deref() {
+            Some("service_account") => AuthMethod::ServiceAccount,
+            Some("metadata") | Some("workload_identity") => AuthMethod::Metadata,
+            Some("installed") | None => AuthMethod::Installed,
+            Some(other) => {
+                log::warn!("Unknown AUTH_METHOD '{other}', falling back to installed flow");
+                AuthMethod::Installed
+            }
+        }
+    }
+}
+
+fn build_https_connector() -> HttpsConnector<HttpConnector> {
+    HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .enable_http2()
+        .build()
+}
+
+pub async fn authenticate(client_secret_file_path: &str) -> anyhow::Result<(SheetsClient, SheetsAuthenticator)> {
+    let auth = match AuthMethod::from_env() {
+        AuthMethod::Installed => authenticate_installed(client_secret_file_path).await?,
+        AuthMethod::ServiceAccount => authenticate_service_account().await?,
+        AuthMethod::Metadata => authenticate_metadata().await?,
+    };
+
+    let hub = Sheets::new(Client::builder().build(build_https_connector()), auth.clone());
+    Ok((hub, auth))
+}
+
+async fn authenticate_installed(client_secret_file_path: &str) -> anyhow::Result<SheetsAuthenticator> {
+    info!("Authenticating with the interactive installed flow");
+    let secret = oauth2::read_application_secret(client_secret_file_path).await?;
+    let auth = InstalledFlowAuthenticator::builder(secret, InstalledFlowReturnMethod::HTTPRedirect)
+        .persist_tokens_to_disk("token.json")
+        .build()
+        .await?;
+    Ok(auth)
+}
+
+async fn authenticate_service_account() -> anyhow::Result<SheetsAuthenticator> {
+    use anyhow::Context;
+
+    let key_path = std::env::var("SERVICE_ACCOUNT_KEY")
+        .context("SERVICE_ACCOUNT_KEY not found in env (required when AUTH_METHOD=service_account)")?;
+    info!("Authenticating with service account key at {key_path}");
+    let key = oauth2::read_service_account_key(&key_path).await?;
+    let auth = ServiceAccountAuthenticator::builder(key).build().await?;
+    Ok(auth)
+}
+
+async fn authenticate_metadata() -> anyhow::Result<SheetsAuthenticator> {
+    let opts = ApplicationDefaultCredentialsFlowOpts::default();
+    let auth = match ApplicationDefaultCredentialsAuthenticator::builder(opts).await {
+        sheets4::oauth2::authenticator::ApplicationDefaultCredentialsTypes::InstanceMetadata(builder) => {
+            info!("Authenticating via the instance metadata server (workload identity)");
+            builder.build().await?
+        }
+        // Application default credentials use a key file instead of the
+        // metadata server when GOOGLE_APPLICATION_CREDENTIALS names one.
+        sheets4::oauth2::authenticator::ApplicationDefaultCredentialsTypes::ServiceAccount(builder) => {
+            info!("Authenticating with the service account key named by GOOGLE_APPLICATION_CREDENTIALS");
+            builder.build().await?
+        }
+    };
+    Ok(auth)
+}
@@ -0,0 +1,112 @@
+use serde_json::Value;
+
+type Row = Vec<Value>;
+
+/// Classification of a row between two sheet snapshots, produced by
+/// [`diff_rows`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowDiff<'a> {
+    Unchanged(&'a Row),
+    Modified { old: &'a Row, new: &'a Row },
+    Added(&'a Row),
+    Removed(&'a Row),
+}
+
+/// Diffs `old` against `new`, detecting inserted/deleted rows instead of
+/// assuming they line up positionally. Falls back to a plain positional
+/// comparison above `max_rows` per side, since the LCS table is quadratic.
+pub fn diff_rows<'a>(old: &'a [Row], new: &'a [Row], max_rows: usize) -> Vec<RowDiff<'a>> {
+    if old.len() > max_rows || new.len() > max_rows {
+        zip_diff(old, new)
+    } else {
+        lcs_diff(old, new)
+    }
+}
+
+fn zip_diff<'a>(old: &'a [Row], new: &'a [Row]) -> Vec<RowDiff<'a>> {
+    let mut result = Vec::with_capacity(old.len().max(new.len()));
+    for (old_row, new_row) in old.iter().zip(new.iter()) {
+        if old_row == new_row {
+            result.push(RowDiff::Unchanged(new_row));
+        } else {
+            result.push(RowDiff::Modified { old: old_row, new: new_row });
+        }
+    }
+    if new.len() > old.len() {
+        result.extend(new[old.len()..].iter().map(RowDiff::Added));
+    } else if old.len() > new.len() {
+        result.extend(old[new.len()..].iter().map(RowDiff::Removed));
+    }
+    result
+}
+
+enum Op<'a> {
+    Keep(&'a Row),
+    Insert(&'a Row),
+    Remove(&'a Row),
+}
+
+// Backtracks the classic Myers/LCS DP table into a forward edit script.
+fn lcs_ops<'a>(old: &'a [Row], new: &'a [Row]) -> Vec<Op<'a>> {
+    let n = old.len();
+    let m = new.len();
+
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in 1..=n {
+        for j in 1..=m {
+            lengths[i][j] = if old[i - 1] == new[j - 1] {
+                lengths[i - 1][j - 1] + 1
+            } else {
+                lengths[i - 1][j].max(lengths[i][j - 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        if i > 0 && j > 0 && old[i - 1] == new[j - 1] {
+            ops.push(Op::Keep(&old[i - 1]));
+            i -= 1;
+            j -= 1;
+        } else if j > 0 && (i == 0 || lengths[i][j - 1] >= lengths[i - 1][j]) {
+            ops.push(Op::Insert(&new[j - 1]));
+            j -= 1;
+        } else {
+            ops.push(Op::Remove(&old[i - 1]));
+            i -= 1;
+        }
+    }
+    ops.reverse();
+    ops
+}
+
+// Pairs an immediately adjacent remove+insert into a single `Modified`
+// rather than reporting them as an unrelated removal and addition.
+fn lcs_diff<'a>(old: &'a [Row], new: &'a [Row]) -> Vec<RowDiff<'a>> {
+    let ops = lcs_ops(old, new);
+    let mut result = Vec::with_capacity(ops.len());
+    let mut i = 0;
+    while i < ops.len() {
+        match &ops[i] {
+            Op::Keep(row) => {
+                result.push(RowDiff::Unchanged(row));
+                i += 1;
+            }
+            Op::Remove(old_row) => {
+                if let Some(Op::Insert(new_row)) = ops.get(i + 1) {
+                    result.push(RowDiff::Modified { old: old_row, new: new_row });
+                    i += 2;
+                } else {
+                    result.push(RowDiff::Removed(old_row));
+                    i += 1;
+                }
+            }
+            Op::Insert(new_row) => {
+                result.push(RowDiff::Added(new_row));
+                i += 1;
+            }
+        }
+    }
+    result
+}
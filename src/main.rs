@@ -1,64 +1,227 @@
+mod api;
+mod auth;
+mod diff;
+mod storage;
+mod template;
+mod webhook;
+
 use std::collections::HashMap;
 use std::error::Error;
 use std::iter::Iterator;
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Context;
 use google_sheets4 as sheets4;
-use google_sheets4::hyper::Client;
-use google_sheets4::hyper::client::HttpConnector;
-use google_sheets4::hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use log::{debug, error, info, warn};
-use reqwest::Response;
+use rand::Rng;
 use serde_json::Value;
-use sheets4::oauth2::{self, InstalledFlowAuthenticator, InstalledFlowReturnMethod};
-use sheets4::Sheets;
 use tokio;
+use tokio::sync::Mutex;
+
+use auth::authenticate;
+use storage::Storage;
+use template::RowTemplate;
+use webhook::WebhookQueue;
 
-type SheetsClient = Sheets<HttpsConnector<HttpConnector>>;
+type SheetsClient = auth::SheetsClient;
 type SheetsContent = Vec<Vec<Value>>;
+/// `ids.txt` contents, shared (not cloned) between `SharedState` and the
+/// `{{mention}}` Handlebars helper so `POST /reload-ids` updates both at
+/// once. A `std::sync::Mutex` since the helper renders synchronously.
+pub(crate) type SharedIds = Arc<std::sync::Mutex<HashMap<String, String>>>;
+
+/// Immutable per-run configuration, shared between the poll loop and the
+/// status API.
+pub(crate) struct Config {
+    hub: SheetsClient,
+    spreadsheet_id: String,
+    range: String,
+    request_timeout: Duration,
+    webhook_queue: WebhookQueue,
+    header: Vec<String>,
+    webhook_template: Option<RowTemplate>,
+    max_diff_rows: usize,
+    storage: Option<Storage>,
+}
+
+/// The bits of state that change at runtime and that both the poll loop
+/// and the status API read or mutate.
+pub(crate) struct SharedState {
+    ids: SharedIds,
+    current_data: Mutex<SheetsContent>,
+    status: Mutex<ApiStatus>,
+    // Serializes run_tick's read-diff-write section so a scheduled poll and
+    // a manual /trigger can't race and double-send.
+    tick_lock: Mutex<()>,
+}
+
+/// Snapshot of the bot's health, served as JSON from `GET /status`.
+#[derive(Default, Clone, serde::Serialize)]
+pub(crate) struct ApiStatus {
+    last_poll_unix_secs: Option<u64>,
+    row_count: usize,
+    last_error: Option<String>,
+    current_backoff_secs: f64,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
 
-    let hub = authenticate("client_secret.json").await?;
+    let (hub, authenticator) = authenticate("client_secret.json").await?;
     let spreadsheet_id = std::env::var("SPREADSHEET_ID").context("SPREADSHEET_ID not found in env")?;
     let range = std::env::var("RANGE").context("RANGE not found in env")?;
     let webhook_url = std::env::var("WEBHOOK_URL").context("WEBHOOK_URL not found in env")?;
 
-    let ids = load_ids("ids.txt");
-    info!("Loaded ids: {:?}", ids);
+    let ids: SharedIds = Arc::new(std::sync::Mutex::new(load_ids("ids.txt")));
+    info!("Loaded ids: {:?}", ids.lock().unwrap());
 
-    let mut current_data = get_sheet_values(&hub, &spreadsheet_id, &range).await?;
+    let header = load_header(&hub, &spreadsheet_id).await?;
+    let webhook_template = load_webhook_template(&header, ids.clone())?;
+    let max_diff_rows: usize = std::env::var("MAX_DIFF_ROWS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(2000);
+
+    let poll_interval = env_duration_secs("POLL_INTERVAL", 5);
+    let request_timeout = env_duration_secs("REQUEST_TIMEOUT", 5);
+    let max_poll_interval = env_duration_secs("MAX_POLL_INTERVAL", 300);
+
+    let webhook_queue = WebhookQueue::spawn(webhook_url);
+    let storage = Storage::from_env(authenticator)?;
+
+    let current_data = load_initial_data(&storage, &hub, &spreadsheet_id, &range).await?;
     info!("Initial data: {}", serde_json::to_string(&current_data)?);
-    info!("Starting loop");
-    send_webhook_message(&webhook_url, format!("Bot started ({} custom ids, {} lines in sheet)",
-                                               ids.len().to_string(),
-                                               current_data.len())).await?;
+    webhook_queue.send(format!("Bot started ({} custom ids, {} lines in sheet)",
+                               ids.lock().unwrap().len(),
+                               current_data.len()))?;
+
+    let config = Arc::new(Config {
+        hub,
+        spreadsheet_id,
+        range,
+        request_timeout,
+        webhook_queue,
+        header,
+        webhook_template,
+        max_diff_rows,
+        storage,
+    });
+    let state = Arc::new(SharedState {
+        ids,
+        current_data: Mutex::new(current_data),
+        status: Mutex::new(ApiStatus::default()),
+        tick_lock: Mutex::new(()),
+    });
+
+    if let Some(listen_addr) = std::env::var("API_LISTEN_ADDR").ok() {
+        let addr = listen_addr.parse().context("API_LISTEN_ADDR is not a valid socket address")?;
+        api::spawn(addr, config.clone(), state.clone());
+    }
 
+    info!("Starting loop");
     let mut last_error_time: Option<std::time::Instant> = None;
+    let mut poll_backoff = poll_interval;
 
     loop {
-        tokio::time::sleep(Duration::from_secs(5)).await;
+        tokio::time::sleep(jittered(poll_backoff)).await;
 
-        match tick(&hub, &spreadsheet_id, &range, &webhook_url, &ids, &current_data).await {
-            Ok(new_data) => current_data = new_data,
+        match run_tick(&config, &state).await {
+            Ok(_) => poll_backoff = poll_interval,
             Err(AppError::GoogleAPI(e)) => {
                 error!("{:?}", e);
+                poll_backoff = (poll_backoff * 2).min(max_poll_interval);
                 if last_error_time.map_or(true, |t| t.elapsed().as_secs() > 60 * 10) {
                     last_error_time = Some(std::time::Instant::now());
-                    let _ = send_webhook_message(&webhook_url, "Google API Error happened. Check console for more information".to_string()).await;
+                    let _ = config.webhook_queue.send("Google API Error happened. Check console for more information".to_string());
                 }
             }
-            Err(AppError::Timeout) => warn!("Request timed out"),
+            Err(AppError::Timeout) => {
+                warn!("Request timed out");
+                poll_backoff = (poll_backoff * 2).min(max_poll_interval);
+            }
             Err(AppError::Other(e)) => error!("{:?}", e),
         }
+
+        state.status.lock().await.current_backoff_secs = poll_backoff.as_secs_f64();
+    }
+}
+
+/// Runs one tick against the shared state. Shared by the poll loop and the
+/// status API's `POST /trigger`.
+pub(crate) async fn run_tick(config: &Config, state: &SharedState) -> Result<SheetsContent, AppError> {
+    let _tick_guard = state.tick_lock.lock().await;
+
+    let previous_data = state.current_data.lock().await.clone();
+    let ids = state.ids.lock().unwrap().clone();
+
+    let result = tick(config, &ids, &previous_data).await;
+
+    let mut status = state.status.lock().await;
+    status.last_poll_unix_secs = Some(unix_now());
+    match &result {
+        Ok(new_data) => {
+            status.row_count = new_data.len();
+            status.last_error = None;
+        }
+        Err(e) => status.last_error = Some(e.to_string()),
+    }
+    drop(status);
+
+    if let Ok(new_data) = &result {
+        *state.current_data.lock().await = new_data.clone();
+    }
+
+    result
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Reads `name` from the env as a whole number of seconds, falling back to
+/// `default_secs` when unset or unparsable.
+fn env_duration_secs(name: &str, default_secs: u64) -> Duration {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(default_secs))
+}
+
+// Adds up to 10% random jitter so repeated backoffs don't all retry in lockstep.
+fn jittered(interval: Duration) -> Duration {
+    let jitter_fraction = rand::thread_rng().gen_range(0.0..0.1);
+    interval + interval.mul_f64(jitter_fraction)
+}
+
+// Restores the last snapshot from storage if configured and present,
+// falling back to a live read of the sheet otherwise.
+async fn load_initial_data(
+    storage: &Option<Storage>,
+    hub: &SheetsClient,
+    spreadsheet_id: &str,
+    range: &str,
+) -> anyhow::Result<SheetsContent> {
+    if let Some(storage) = storage {
+        match storage.load_snapshot().await {
+            Ok(Some(snapshot)) => {
+                info!("Restored last snapshot from GCS ({} rows)", snapshot.len());
+                return Ok(snapshot);
+            }
+            Ok(None) => info!("No snapshot found in GCS yet, reading the sheet fresh"),
+            Err(e) => warn!("Failed to load snapshot from GCS, reading the sheet fresh: {e:?}"),
+        }
     }
+    Ok(get_sheet_values(hub, spreadsheet_id, range).await?)
 }
 
 #[derive(thiserror::Error, Debug)]
-enum AppError {
+pub(crate) enum AppError {
     #[error("Google API error: {0}")]
     GoogleAPI(#[from] sheets4::Error),
     #[error("Request timed out")]
@@ -67,42 +230,116 @@ enum AppError {
     Other(#[from] anyhow::Error),
 }
 
-async fn tick(hub: &SheetsClient,
-              spreadsheet_id: &str,
-              range: &str,
-              webhook_url: &str,
-              ids: &HashMap<String, String>,
-              previous_data: &SheetsContent) -> Result<SheetsContent, AppError> {
-    let new_data: SheetsContent = get_sheet_values_timeout(&hub, &spreadsheet_id, &range).await?;
+async fn tick(config: &Config, ids: &HashMap<String, String>, previous_data: &SheetsContent) -> Result<SheetsContent, AppError> {
+    let new_data: SheetsContent = get_sheet_values_timeout(&config.hub, &config.spreadsheet_id, &config.range, config.request_timeout).await?;
     debug!("New data: {}", serde_json::to_string(&new_data).context("Failed to deserialize new data")?);
-    for (new_row, old_row) in new_data.iter().zip(previous_data.iter()) {
-        if new_row != old_row {
-            info!("New row difference found at {:?}", serde_json::to_string(new_row));
-            let content = new_row
-                .iter()
-                .map(|value| value.to_string())
-                .collect::<Vec<String>>()
-                .join(", ");
 
-            let numero_aluno = new_row.get(0).context("No first row")?.as_str().context("First row not a string")?.to_uppercase();
-            let extra = ids.get(numero_aluno.as_str()).map(|id| format!("<@{id}> ")).unwrap_or_default();
+    let header = &config.header;
+    let webhook_template = &config.webhook_template;
 
-            let content = format!("{}{}", extra, content);
+    let mut row_index = 0usize;
+    let mut changes = Vec::new();
+    for row_diff in diff::diff_rows(previous_data, &new_data, config.max_diff_rows) {
+        if !matches!(row_diff, diff::RowDiff::Unchanged(_)) {
+            changes.push(row_diff);
+        }
+        match row_diff {
+            diff::RowDiff::Unchanged(_) => row_index += 1,
+            diff::RowDiff::Modified { old, new } => {
+                info!("Row changed at index {row_index}: {:?}", serde_json::to_string(new));
+                let changed_cells = template::changed_cells(header, old, new);
+                let content = match webhook_template {
+                    Some(tmpl) => tmpl.render(row_index, new, &changed_cells).context("Failed to render webhook template")?,
+                    None => {
+                        let cols = changed_cells.iter().map(|c| c.column.clone()).collect::<Vec<_>>().join(", ");
+                        format!("Row changed: cols {cols}\n{}", legacy_row_content(new, ids)?)
+                    }
+                };
+                config.webhook_queue.send(content)?;
+                row_index += 1;
+            }
+            diff::RowDiff::Added(new) => {
+                info!("Row added at index {row_index}: {:?}", serde_json::to_string(new));
+                let changed_cells = template::changed_cells_whole_row(header, new, false);
+                let content = match webhook_template {
+                    Some(tmpl) => tmpl.render(row_index, new, &changed_cells).context("Failed to render webhook template")?,
+                    None => format!("Row added\n{}", legacy_row_content(new, ids)?),
+                };
+                config.webhook_queue.send(content)?;
+                row_index += 1;
+            }
+            diff::RowDiff::Removed(old) => {
+                info!("Row removed: {:?}", serde_json::to_string(old));
+                let changed_cells = template::changed_cells_whole_row(header, old, true);
+                let content = match webhook_template {
+                    Some(tmpl) => tmpl.render(row_index, old, &changed_cells).context("Failed to render webhook template")?,
+                    None => format!("Row removed\n{}", legacy_row_content(old, ids)?),
+                };
+                config.webhook_queue.send(content)?;
+            }
+        }
+    }
 
-            send_webhook_message(webhook_url, &content).await?;
+    if let Some(storage) = &config.storage {
+        if let Err(e) = storage.persist(&new_data, &changes).await {
+            error!("Failed to persist sheet state to GCS: {e:?}");
         }
     }
+
     Ok(new_data)
 }
 
-async fn send_webhook_message<S: Into<String>>(webhook_url: &str, content: S) -> anyhow::Result<Response> {
-    reqwest::Client::new()
-        .post(webhook_url)
-        .json(&serde_json::json!({"content": content.into()}))
-        .send().await.context("Failed to send webhook message")
+/// Reproduces the bot's original message body (mention prefix plus a
+/// comma-joined row) for when no `WEBHOOK_TEMPLATE` is configured.
+fn legacy_row_content(row: &[Value], ids: &HashMap<String, String>) -> anyhow::Result<String> {
+    let content = row
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let numero_aluno = row.get(0).context("No first row")?.as_str().context("First row not a string")?.to_uppercase();
+    let extra = ids.get(numero_aluno.as_str()).map(|id| format!("<@{id}> ")).unwrap_or_default();
+
+    Ok(format!("{}{}", extra, content))
+}
+
+/// Reads the sheet's header row once at startup via `HEADER_RANGE`, if
+/// configured, so columns can be named in diff messages and templates.
+async fn load_header(hub: &SheetsClient, spreadsheet_id: &str) -> anyhow::Result<Vec<String>> {
+    let Some(header_range) = std::env::var("HEADER_RANGE").ok() else {
+        return Ok(Vec::new());
+    };
+
+    let header_row = get_sheet_values(hub, spreadsheet_id, &header_range)
+        .await?
+        .into_iter()
+        .next()
+        .context("HEADER_RANGE returned no rows")?;
+    let header: Vec<String> = header_row
+        .iter()
+        .map(|value| value.as_str().unwrap_or_default().to_string())
+        .collect();
+    info!("Loaded header: {header:?}");
+    Ok(header)
+}
+
+/// Loads the Handlebars template configured via `WEBHOOK_TEMPLATE`, if any,
+/// exposing `header`'s columns as named template variables.
+fn load_webhook_template(header: &[String], ids: SharedIds) -> anyhow::Result<Option<RowTemplate>> {
+    let Some(template_path) = std::env::var("WEBHOOK_TEMPLATE").ok() else {
+        return Ok(None);
+    };
+
+    if header.is_empty() {
+        warn!("WEBHOOK_TEMPLATE is set but HEADER_RANGE isn't: named columns like {{{{StudentNumber}}}} will render blank");
+    }
+
+    info!("Loading webhook template from {template_path}");
+    Ok(Some(RowTemplate::load(&template_path, header.to_vec(), ids)?))
 }
 
-fn load_ids(ids_path: &str) -> HashMap<String, String> {
+pub(crate) fn load_ids(ids_path: &str) -> HashMap<String, String> {
     std::fs::read_to_string(ids_path).and_then(
         |content| Ok(content.lines().filter_map(|line| {
             let mut parts = line.split_whitespace();
@@ -119,26 +356,9 @@ async fn get_sheet_values(sheets: &SheetsClient, spreadsheet_id: &str, range: &s
     Ok(values)
 }
 
-async fn get_sheet_values_timeout(sheets: &SheetsClient, spreadsheet_id: &str, range: &str) -> Result<SheetsContent, AppError> {
+async fn get_sheet_values_timeout(sheets: &SheetsClient, spreadsheet_id: &str, range: &str, timeout: Duration) -> Result<SheetsContent, AppError> {
     tokio::time::timeout(
-        Duration::from_secs(5),
+        timeout,
         get_sheet_values(sheets, spreadsheet_id, range),
     ).await.map_err(|_| AppError::Timeout)?
-}
-
-async fn authenticate(client_secret_file_path: &str) -> Result<SheetsClient, Box<dyn Error>> {
-    let secret = oauth2::read_application_secret(client_secret_file_path).await?;
-    let auth = InstalledFlowAuthenticator::builder(secret, InstalledFlowReturnMethod::HTTPRedirect)
-        .persist_tokens_to_disk("token.json")
-        .build()
-        .await?;
-
-    let connector = HttpsConnectorBuilder::new()
-        .with_native_roots()
-        .https_or_http()
-        .enable_http1()
-        .enable_http2()
-        .build();
-    let hub = Sheets::new(Client::builder().build(connector), auth);
-    Ok(hub)
 }
\ No newline at end of file
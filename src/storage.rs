@@ -0,0 +1,151 @@
+use anyhow::Context;
+use log::info;
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::auth::{AuthMethod, SheetsAuthenticator};
+use crate::diff::RowDiff;
+use crate::SheetsContent;
+
+const GCS_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+/// Optional GCS-backed persistence for the latest sheet snapshot and a
+/// running change-history log, so a restart can diff against real previous
+/// state instead of treating the first read after boot as ground truth.
+/// Built from `GCS_BUCKET`; absent that env var the subsystem is simply not
+/// constructed, so callers thread an `Option<Storage>`.
+pub struct Storage {
+    client: reqwest::Client,
+    auth: SheetsAuthenticator,
+    bucket: String,
+    snapshot_object: String,
+    history_object: String,
+}
+
+impl Storage {
+    /// Reads `GCS_BUCKET` (required), `GCS_SNAPSHOT_OBJECT` and
+    /// `GCS_HISTORY_OBJECT` (optional, with defaults). Returns `Ok(None)`
+    /// when `GCS_BUCKET` is unset. Fails fast under `AUTH_METHOD=installed`:
+    /// GCS reuses the Sheets authenticator, but an installed flow's
+    /// `token.json` isn't consented to the GCS scope and can't mint one
+    /// without an interactive re-consent.
+    pub fn from_env(auth: SheetsAuthenticator) -> anyhow::Result<Option<Self>> {
+        let Some(bucket) = std::env::var("GCS_BUCKET").ok() else {
+            return Ok(None);
+        };
+        anyhow::ensure!(
+            !matches!(AuthMethod::from_env(), AuthMethod::Installed),
+            "GCS_BUCKET is set but AUTH_METHOD is 'installed' (or unset): the installed flow's \
+             token.json isn't consented to the GCS scope and can't mint one unattended. Use \
+             AUTH_METHOD=service_account or AUTH_METHOD=metadata when GCS persistence is enabled."
+        );
+
+        let snapshot_object = std::env::var("GCS_SNAPSHOT_OBJECT").unwrap_or_else(|_| "snapshot.json".to_string());
+        let history_object = std::env::var("GCS_HISTORY_OBJECT").unwrap_or_else(|_| "history.jsonl".to_string());
+
+        info!("Persisting sheet state to gs://{bucket}/{snapshot_object} (history: gs://{bucket}/{history_object})");
+        Ok(Some(Self { client: reqwest::Client::new(), auth, bucket, snapshot_object, history_object }))
+    }
+
+    /// Loads the last persisted snapshot, if one exists.
+    pub async fn load_snapshot(&self) -> anyhow::Result<Option<SheetsContent>> {
+        let Some(body) = self.download_object(&self.snapshot_object).await? else {
+            return Ok(None);
+        };
+        let content = serde_json::from_str(&body).context("Failed to parse snapshot from GCS")?;
+        Ok(Some(content))
+    }
+
+    /// Writes the latest snapshot and appends each diff to the history log.
+    /// A no-op when `diffs` is empty, so an unchanged sheet doesn't
+    /// re-upload an identical snapshot every poll interval.
+    pub async fn persist(&self, snapshot: &SheetsContent, diffs: &[RowDiff<'_>]) -> anyhow::Result<()> {
+        if diffs.is_empty() {
+            return Ok(());
+        }
+        self.upload_object(&self.snapshot_object, "application/json", serde_json::to_string(snapshot)?).await?;
+        self.append_history(diffs).await?;
+        Ok(())
+    }
+
+    // GCS objects have no native append: download, append in memory, rewrite.
+    // Fine at the row-change volume this bot deals with.
+    async fn append_history(&self, diffs: &[RowDiff<'_>]) -> anyhow::Result<()> {
+        let mut body = self.download_object(&self.history_object).await?.unwrap_or_default();
+        for diff in diffs {
+            body.push_str(&serde_json::to_string(&HistoryEntry::from(diff))?);
+            body.push('\n');
+        }
+        self.upload_object(&self.history_object, "application/x-ndjson", body).await
+    }
+
+    async fn download_object(&self, object_name: &str) -> anyhow::Result<Option<String>> {
+        let url = format!(
+            "https://storage.googleapis.com/storage/v1/b/{}/o/{}?alt=media",
+            self.bucket,
+            urlencode_object(object_name),
+        );
+        let response = self
+            .client
+            .get(&url)
+            .bearer_auth(self.token().await?)
+            .send()
+            .await
+            .with_context(|| format!("Failed to fetch gs://{}/{object_name}", self.bucket))?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response
+            .error_for_status()
+            .with_context(|| format!("GCS returned an error fetching gs://{}/{object_name}", self.bucket))?;
+        Ok(Some(response.text().await.context("Failed to read GCS object body")?))
+    }
+
+    async fn upload_object(&self, object_name: &str, content_type: &str, body: String) -> anyhow::Result<()> {
+        let url = format!(
+            "https://storage.googleapis.com/upload/storage/v1/b/{}/o?uploadType=media&name={}",
+            self.bucket,
+            urlencode_object(object_name),
+        );
+        self.client
+            .post(&url)
+            .bearer_auth(self.token().await?)
+            .header("Content-Type", content_type)
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to upload gs://{}/{object_name}", self.bucket))?
+            .error_for_status()
+            .with_context(|| format!("GCS rejected the upload of gs://{}/{object_name}", self.bucket))?;
+        Ok(())
+    }
+
+    async fn token(&self) -> anyhow::Result<String> {
+        let token = self.auth.token(&[GCS_SCOPE]).await.context("Failed to mint a GCS access token")?;
+        Ok(token.token().unwrap_or_default().to_string())
+    }
+}
+
+fn urlencode_object(name: &str) -> String {
+    // GCS object names may contain '/', which the JSON API expects encoded.
+    name.replace('/', "%2F")
+}
+
+#[derive(serde::Serialize)]
+struct HistoryEntry<'a> {
+    kind: &'static str,
+    old: Option<&'a Vec<Value>>,
+    new: Option<&'a Vec<Value>>,
+}
+
+impl<'a> From<&RowDiff<'a>> for HistoryEntry<'a> {
+    fn from(diff: &RowDiff<'a>) -> Self {
+        match *diff {
+            RowDiff::Unchanged(row) => HistoryEntry { kind: "unchanged", old: Some(row), new: Some(row) },
+            RowDiff::Modified { old, new } => HistoryEntry { kind: "modified", old: Some(old), new: Some(new) },
+            RowDiff::Added(new) => HistoryEntry { kind: "added", old: None, new: Some(new) },
+            RowDiff::Removed(old) => HistoryEntry { kind: "removed", old: Some(old), new: None },
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::Context;
+use handlebars::{Context as HbContext, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderError};
+use serde_json::Value;
+
+use crate::SharedIds;
+
+/// A cell whose value differs between the previous and new row, exposed to
+/// templates via `{{#each changed_cells}}`.
+#[derive(Clone, serde::Serialize)]
+pub struct ChangedCell {
+    pub column: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// Renders a row through a Handlebars template, with the sheet's header
+/// exposed as named variables plus `row_index`, `changed_cells` and the
+/// `{{mention ...}}` helper.
+pub struct RowTemplate {
+    handlebars: Handlebars<'static>,
+    header: Vec<String>,
+}
+
+impl RowTemplate {
+    pub fn load(template_path: &str, header: Vec<String>, ids: SharedIds) -> anyhow::Result<Self> {
+        let source = std::fs::read_to_string(template_path)
+            .with_context(|| format!("Failed to read webhook template at {template_path}"))?;
+
+        let mut handlebars = Handlebars::new();
+        handlebars
+            .register_template_string("message", source)
+            .with_context(|| format!("Failed to parse webhook template at {template_path}"))?;
+        handlebars.register_helper("mention", Box::new(MentionHelper { ids }));
+
+        Ok(Self { handlebars, header })
+    }
+
+    pub fn render(&self, row_index: usize, row: &[Value], changed_cells: &[ChangedCell]) -> anyhow::Result<String> {
+        let mut fields = serde_json::Map::new();
+        for (name, value) in self.header.iter().zip(row.iter()) {
+            fields.insert(name.clone(), value.clone());
+        }
+        fields.insert("row_index".to_string(), Value::from(row_index));
+        fields.insert("changed_cells".to_string(), serde_json::to_value(changed_cells)?);
+
+        self.handlebars
+            .render("message", &Value::Object(fields))
+            .context("Failed to render webhook template")
+    }
+}
+
+/// Diffs two rows column by column, naming each differing column from
+/// `header` (falling back to its index). Covers the longer of the two rows,
+/// so columns dropped off the tail are still reported as changed.
+pub fn changed_cells(header: &[String], old_row: &[Value], new_row: &[Value]) -> Vec<ChangedCell> {
+    (0..old_row.len().max(new_row.len()))
+        .filter_map(|i| {
+            let old_value = old_row.get(i).cloned().unwrap_or(Value::Null);
+            let new_value = new_row.get(i).cloned().unwrap_or(Value::Null);
+            if old_value == new_value {
+                return None;
+            }
+            let column = header
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| i.to_string());
+            Some(ChangedCell { column, old: old_value, new: new_value })
+        })
+        .collect()
+}
+
+/// Every column of `row` reported as changed from/into nothing, for a
+/// whole-row addition or removal.
+pub fn changed_cells_whole_row(header: &[String], row: &[Value], removed: bool) -> Vec<ChangedCell> {
+    row.iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let column = header.get(i).cloned().unwrap_or_else(|| i.to_string());
+            if removed {
+                ChangedCell { column, old: value.clone(), new: Value::Null }
+            } else {
+                ChangedCell { column, old: Value::Null, new: value.clone() }
+            }
+        })
+        .collect()
+}
+
+/// `{{mention SomeColumn}}` looks the uppercased column value up against
+/// `ids.txt` and writes the Discord mention string, or nothing if no match.
+/// Shares `ids` with `SharedState` so `POST /reload-ids` takes effect here too.
+struct MentionHelper {
+    ids: SharedIds,
+}
+
+impl HelperDef for MentionHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HbContext,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let key = h
+            .param(0)
+            .and_then(|v| v.value().as_str())
+            .ok_or_else(|| RenderError::new("mention helper requires a string argument"))?
+            .to_uppercase();
+
+        if let Some(id) = self.ids.lock().unwrap().get(&key) {
+            out.write(&format!("<@{id}>"))?;
+        }
+        Ok(())
+    }
+}
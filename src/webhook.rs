@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use log::{error, warn};
+use rand::Rng;
+use reqwest::{Response, StatusCode};
+use tokio::sync::mpsc;
+
+const MAX_RETRIES: u32 = 5;
+const MAX_RATE_LIMIT_RETRIES: u32 = 10;
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// Comfortably under Discord's per-webhook limit (5 requests / 2s), so a
+// tick with many changed rows doesn't fire them back-to-back and trip a 429.
+const MIN_SEND_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Serializes outbound Discord webhook messages through a single background
+/// task so a burst of row changes in one `tick` can't flood the webhook.
+pub struct WebhookQueue {
+    sender: mpsc::UnboundedSender<String>,
+}
+
+impl WebhookQueue {
+    pub fn spawn(webhook_url: String) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run(webhook_url, receiver));
+        Self { sender }
+    }
+
+    /// Enqueues `content` for delivery. Errors only if the worker task died.
+    pub fn send<S: Into<String>>(&self, content: S) -> anyhow::Result<()> {
+        self.sender
+            .send(content.into())
+            .context("Webhook queue worker is no longer running")
+    }
+}
+
+async fn run(webhook_url: String, mut receiver: mpsc::UnboundedReceiver<String>) {
+    let mut last_sent = None;
+    while let Some(content) = receiver.recv().await {
+        if let Some(last_sent) = last_sent {
+            let elapsed: Duration = std::time::Instant::now().duration_since(last_sent);
+            if elapsed < MIN_SEND_INTERVAL {
+                tokio::time::sleep(MIN_SEND_INTERVAL - elapsed).await;
+            }
+        }
+        last_sent = Some(std::time::Instant::now());
+
+        if let Err(e) = send_with_retry(&webhook_url, &content).await {
+            error!("Giving up on webhook message: {e:?}");
+        }
+    }
+}
+
+// Honours Discord's 429 `Retry-After` (capped at MAX_RATE_LIMIT_RETRIES) and
+// retries 5xx with capped exponential backoff. Other 4xx is permanent.
+async fn send_with_retry(webhook_url: &str, content: &str) -> anyhow::Result<()> {
+    let mut attempt = 0u32;
+    let mut rate_limit_attempt = 0u32;
+    loop {
+        let response = post(webhook_url, content).await?;
+        let status = response.status();
+
+        if status.is_success() {
+            return Ok(());
+        }
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            rate_limit_attempt += 1;
+            if rate_limit_attempt > MAX_RATE_LIMIT_RETRIES {
+                anyhow::bail!("Webhook kept getting rate limited after {MAX_RATE_LIMIT_RETRIES} retries");
+            }
+            let retry_after = retry_after_seconds(&response).unwrap_or(1.0);
+            warn!("Discord rate limited the webhook, waiting {retry_after}s before retrying ({rate_limit_attempt}/{MAX_RATE_LIMIT_RETRIES})");
+            tokio::time::sleep(Duration::from_secs_f64(retry_after)).await;
+            continue;
+        }
+
+        if status.is_server_error() {
+            attempt += 1;
+            if attempt > MAX_RETRIES {
+                anyhow::bail!("Webhook kept failing with {status} after {MAX_RETRIES} retries");
+            }
+            let backoff = backoff_with_jitter(attempt);
+            warn!("Webhook returned {status}, retrying in {backoff:?} (attempt {attempt}/{MAX_RETRIES})");
+            tokio::time::sleep(backoff).await;
+            continue;
+        }
+
+        anyhow::bail!("Webhook request failed permanently with status {status}");
+    }
+}
+
+async fn post(webhook_url: &str, content: &str) -> anyhow::Result<Response> {
+    reqwest::Client::new()
+        .post(webhook_url)
+        .json(&serde_json::json!({"content": content}))
+        .send()
+        .await
+        .context("Failed to send webhook message")
+}
+
+fn retry_after_seconds(response: &Response) -> Option<f64> {
+    response
+        .headers()
+        .get("Retry-After")
+        .or_else(|| response.headers().get("X-RateLimit-Reset-After"))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<f64>().ok())
+}
+
+fn backoff_with_jitter(attempt: u32) -> Duration {
+    let exponential = BASE_BACKOFF.saturating_mul(1 << attempt.min(6)).min(MAX_BACKOFF);
+    let jitter = Duration::from_millis(rand::thread_rng().gen_range(0..=100));
+    exponential + jitter
+}